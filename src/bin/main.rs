@@ -1,15 +1,17 @@
 use anyhow::Result;
 use aws_config::BehaviorVersion;
-use aws_sdk_secretsmanager::Client;
 use clap::Parser;
-use vrenv::{EnvFileConfig, VrEnv};
+use vrenv::{
+    Backend, EnvFileConfig, EnvironmentMode, SecretSource, SecretsManagerSource, SsmSource, VrEnv,
+    DEFAULT_FLATTEN_MAX_DEPTH,
+};
 
 #[derive(Parser)]
 #[command(name = "vrenv")]
 #[command(about = "A CLI tool to fetch AWS secrets and create environment files")]
 struct Cli {
-    /// AWS Secret ARN
-    #[arg(help = "The ARN of the AWS secret to fetch")]
+    /// AWS Secret ARN, secret name, or (for --backend ssm) parameter name/path
+    #[arg(help = "The ARN of the AWS secret, or the SSM parameter name, to fetch")]
     secret_arn: String,
 
     /// Output directory (defaults to /var/run)
@@ -23,6 +25,51 @@ struct Cli {
     /// AWS region
     #[arg(short, long, default_value = "us-west-2")]
     region: String,
+
+    /// Which AWS service to fetch the secret from
+    #[arg(long, value_enum, default_value = "secrets-manager")]
+    backend: Backend,
+
+    /// Base env file to layer the secret on top of
+    #[arg(long)]
+    base_file: Option<String>,
+
+    /// Prefix applied to emitted keys, and used to pull matching overrides from the process environment
+    #[arg(long)]
+    key_prefix: Option<String>,
+
+    /// Octal mode for the generated env file, e.g. 640 (defaults to 600; VRENV_FILE_MODE env var always wins)
+    #[arg(long)]
+    mode: Option<String>,
+
+    /// Allow writing the env file with a world-readable mode
+    #[arg(long)]
+    allow_world_readable: bool,
+
+    /// Fetch a specific secret version id (Secrets Manager only)
+    #[arg(long)]
+    version_id: Option<String>,
+
+    /// Fetch a secret version stage, e.g. AWSCURRENT or AWSPREVIOUS (Secrets Manager only)
+    #[arg(long)]
+    version_stage: Option<String>,
+
+    /// Output format for the generated env file
+    #[arg(long, value_enum, default_value = "dotenv")]
+    format: EnvironmentMode,
+
+    /// Recursively flatten nested JSON objects/arrays into prefixed keys (e.g. DB_HOST)
+    /// instead of dumping them as a raw JSON blob
+    #[arg(long)]
+    flatten: bool,
+
+    /// Separator used between levels when --flatten is set
+    #[arg(long, default_value = "_")]
+    flatten_separator: String,
+
+    /// Max recursion depth when --flatten is set
+    #[arg(long, default_value_t = DEFAULT_FLATTEN_MAX_DEPTH)]
+    flatten_max_depth: usize,
 }
 
 #[tokio::main]
@@ -35,14 +82,32 @@ async fn main() -> Result<()> {
         .load()
         .await;
 
-    let client = Client::new(&config);
-    let vrenv = VrEnv::new(client);
+    let source: Box<dyn SecretSource> = match cli.backend {
+        Backend::SecretsManager => Box::new(SecretsManagerSource::new(
+            aws_sdk_secretsmanager::Client::new(&config),
+        )),
+        Backend::Ssm => Box::new(SsmSource::new(aws_sdk_ssm::Client::new(&config))),
+    };
+    let vrenv = VrEnv::new(source);
+
+    let file_mode = cli.mode.map(|m| vrenv::parse_octal_mode(&m)).transpose()?;
 
     // Create configuration
     let env_config = EnvFileConfig {
         secret_arn: cli.secret_arn.clone(),
         output_dir: cli.output_dir,
         file_name: cli.name,
+        backend: cli.backend,
+        base_file: cli.base_file,
+        key_prefix: cli.key_prefix,
+        file_mode,
+        allow_world_readable: cli.allow_world_readable,
+        version_id: cli.version_id,
+        version_stage: cli.version_stage,
+        format: cli.format,
+        flatten: cli.flatten,
+        flatten_separator: cli.flatten_separator,
+        flatten_max_depth: cli.flatten_max_depth,
     };
 
     // Fetch the secret and create environment file
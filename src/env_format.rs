@@ -0,0 +1,105 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+/// How an env var map is serialized to the output file.
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum EnvironmentMode {
+    /// Plain `KEY=value` lines (the default)
+    #[default]
+    Dotenv,
+    /// `export KEY='value'` shell form, safe to `source` in bash
+    Export,
+    /// systemd `EnvironmentFile` syntax, quoting values that contain spaces
+    Systemd,
+    /// Raw JSON object
+    Json,
+}
+
+/// Render an env var map according to `mode`.
+pub fn format_env(env_map: &BTreeMap<String, String>, mode: EnvironmentMode) -> Result<String> {
+    match mode {
+        EnvironmentMode::Dotenv => Ok(format_dotenv(env_map)),
+        EnvironmentMode::Export => Ok(format_export(env_map)),
+        EnvironmentMode::Systemd => Ok(format_systemd(env_map)),
+        EnvironmentMode::Json => format_json(env_map),
+    }
+}
+
+fn format_dotenv(env_map: &BTreeMap<String, String>) -> String {
+    let lines: Vec<String> = env_map
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    lines.join("\n") + "\n"
+}
+
+fn format_export(env_map: &BTreeMap<String, String>) -> String {
+    let lines: Vec<String> = env_map
+        .iter()
+        .map(|(key, value)| format!("export {}='{}'", key, escape_single_quotes(value)))
+        .collect();
+    lines.join("\n") + "\n"
+}
+
+/// Escape a value for use inside single quotes in a POSIX shell: close the quote, escape a
+/// literal `'`, then reopen it.
+fn escape_single_quotes(value: &str) -> String {
+    value.replace('\'', r"'\''")
+}
+
+fn format_systemd(env_map: &BTreeMap<String, String>) -> String {
+    let lines: Vec<String> = env_map
+        .iter()
+        .map(|(key, value)| {
+            if value.contains(' ') {
+                format!("{}=\"{}\"", key, value.replace('"', "\\\""))
+            } else {
+                format!("{}={}", key, value)
+            }
+        })
+        .collect();
+    lines.join("\n") + "\n"
+}
+
+fn format_json(env_map: &BTreeMap<String, String>) -> Result<String> {
+    Ok(serde_json::to_string_pretty(env_map)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_map() -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        map.insert("DATABASE_URL".to_string(), "postgres://localhost".to_string());
+        map.insert("GREETING".to_string(), "it's great".to_string());
+        map
+    }
+
+    #[test]
+    fn test_format_dotenv() {
+        let result = format_env(&sample_map(), EnvironmentMode::Dotenv).unwrap();
+        assert!(result.contains("DATABASE_URL=postgres://localhost"));
+    }
+
+    #[test]
+    fn test_format_export_escapes_single_quotes() {
+        let result = format_env(&sample_map(), EnvironmentMode::Export).unwrap();
+        assert!(result.contains(r"export GREETING='it'\''s great'"));
+    }
+
+    #[test]
+    fn test_format_systemd_quotes_values_with_spaces() {
+        let result = format_env(&sample_map(), EnvironmentMode::Systemd).unwrap();
+        assert!(result.contains(r#"GREETING="it's great""#));
+        assert!(result.contains("DATABASE_URL=postgres://localhost"));
+    }
+
+    #[test]
+    fn test_format_json() {
+        let result = format_env(&sample_map(), EnvironmentMode::Json).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["DATABASE_URL"], "postgres://localhost");
+    }
+}
@@ -1,59 +1,127 @@
 use anyhow::{Context, Result};
-use aws_sdk_secretsmanager::Client;
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
+pub mod env_format;
+pub mod secret_source;
+
+pub use env_format::EnvironmentMode;
+pub use secret_source::{
+    Backend, FetchOptions, SecretPayload, SecretSource, SecretsManagerSource, SsmSource,
+};
+
 /// Configuration for creating environment files from AWS secrets
 pub struct EnvFileConfig {
     pub secret_arn: String,
     pub output_dir: String,
     pub file_name: Option<String>,
+    /// Which backend `secret_arn` was fetched from, used to derive a sensible default file
+    /// name when `file_name` isn't given
+    pub backend: Backend,
+    /// Optional base env file whose values are overridden by the fetched secret
+    pub base_file: Option<String>,
+    /// Optional prefix applied to keys emitted from the secret, and used to select which
+    /// process environment variables are layered on top
+    pub key_prefix: Option<String>,
+    /// Octal mode for the generated env file (defaults to 0o600); always overridden by
+    /// the `VRENV_FILE_MODE` environment variable when it is set
+    pub file_mode: Option<u32>,
+    /// Allow writing the env file with a world-readable mode instead of rejecting it
+    pub allow_world_readable: bool,
+    /// Pin a specific secret version (Secrets Manager `VersionId`); ignored by backends that
+    /// don't support versioning
+    pub version_id: Option<String>,
+    /// Pin a secret version stage, e.g. `AWSCURRENT` or `AWSPREVIOUS`; ignored by backends
+    /// that don't support versioning
+    pub version_stage: Option<String>,
+    /// Output format for the generated env file
+    pub format: EnvironmentMode,
+    /// Recursively flatten nested JSON objects/arrays into prefixed keys instead of dumping
+    /// them as a raw JSON blob
+    pub flatten: bool,
+    /// Separator used between levels when `flatten` is set
+    pub flatten_separator: String,
+    /// Max recursion depth when `flatten` is set, guarding against pathological nesting
+    pub flatten_max_depth: usize,
 }
 
+const DEFAULT_FILE_MODE: u32 = 0o600;
+const FILE_MODE_ENV_VAR: &str = "VRENV_FILE_MODE";
+
 /// Main service for handling AWS secrets and environment file creation
 pub struct VrEnv {
-    client: Client,
+    source: Box<dyn SecretSource>,
 }
 
 impl VrEnv {
-    /// Create a new SecretEnvService with the provided AWS client
-    pub fn new(client: Client) -> Self {
-        Self { client }
+    /// Create a new VrEnv backed by the given secret source
+    pub fn new(source: Box<dyn SecretSource>) -> Self {
+        Self { source }
     }
 
-    /// Fetch a secret from AWS Secrets Manager and create an environment file
+    /// Fetch a secret and create an environment file from it, layered on top of an optional
+    /// base file and the process environment (see [`merge_sources`])
     pub async fn create_env_file_from_secret(&self, config: EnvFileConfig) -> Result<String> {
-        // Fetch the secret
-        let secret_value = self.fetch_secret(&config.secret_arn).await?;
+        let fetch_options = FetchOptions {
+            version_id: config.version_id.clone(),
+            version_stage: config.version_stage.clone(),
+        };
+        let payload = self.fetch_secret(&config.secret_arn, &fetch_options).await?;
+        let key_prefix = config.key_prefix.as_deref();
 
-        // Extract name from ARN if not provided
+        // Derive a default file name from the identifier if not provided
         let env_file_name = config
             .file_name
-            .unwrap_or_else(|| extract_secret_name_from_arn(&config.secret_arn));
-
-        // Create the environment file
+            .clone()
+            .unwrap_or_else(|| default_env_file_name(&config.secret_arn, config.backend));
         let output_path = Path::new(&config.output_dir);
         let env_file_path = output_path.join(format!("{}.env", env_file_name));
-        create_env_file(&secret_value, &env_file_path)?;
+        let file_mode = resolve_file_mode(config.file_mode)?;
+
+        let flatten = config.flatten.then(|| FlattenOptions {
+            separator: config.flatten_separator.clone(),
+            max_depth: config.flatten_max_depth,
+        });
+
+        let secret_env = match &payload {
+            SecretPayload::Text(secret_value) => {
+                secret_value_to_env_map(secret_value, key_prefix, flatten.as_ref())?
+            }
+            SecretPayload::Binary(bytes) => {
+                let companion_path = output_path.join(format!("{}.bin", env_file_name));
+                write_secret_file(bytes, &companion_path, file_mode, config.allow_world_readable)
+                    .context("Failed to write binary secret companion file")?;
+
+                let mut env_map = BTreeMap::new();
+                env_map.insert(
+                    prefixed_key("SECRET_VALUE_FILE", key_prefix),
+                    companion_path.to_string_lossy().to_string(),
+                );
+                env_map
+            }
+        };
+        let merged_env = merge_sources(config.base_file.as_deref(), &secret_env, key_prefix)?;
+
+        // Create the environment file
+        create_env_file(
+            &env_format::format_env(&merged_env, config.format)?,
+            &env_file_path,
+            file_mode,
+            config.allow_world_readable,
+        )?;
 
         Ok(env_file_path.to_string_lossy().to_string())
     }
 
-    /// Fetch a secret value from AWS Secrets Manager
-    pub async fn fetch_secret(&self, secret_arn: &str) -> Result<String> {
-        let response = self
-            .client
-            .get_secret_value()
-            .secret_id(secret_arn)
-            .send()
-            .await
-            .context("Failed to fetch secret from AWS")?;
-
-        response
-            .secret_string()
-            .context("Secret does not contain a string value")
-            .map(|s| s.to_string())
+    /// Fetch a secret from the configured backend
+    pub async fn fetch_secret(
+        &self,
+        secret_id: &str,
+        options: &FetchOptions,
+    ) -> Result<SecretPayload> {
+        self.source.fetch(secret_id, options).await
     }
 }
 
@@ -70,54 +138,221 @@ pub fn extract_secret_name_from_arn(arn: &str) -> String {
         .to_string()
 }
 
-/// Create an environment file from a secret value
-pub fn create_env_file(secret_value: &str, file_path: &Path) -> Result<()> {
+/// Extract the last path segment of an SSM parameter name, e.g. "/my/app/db" -> "db"
+pub fn extract_parameter_name_from_path(name: &str) -> String {
+    name.rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or(name)
+        .to_string()
+}
+
+/// Derive a default env file name from a secret identifier, based on which backend it came from
+fn default_env_file_name(secret_id: &str, backend: Backend) -> String {
+    match backend {
+        Backend::SecretsManager => extract_secret_name_from_arn(secret_id),
+        Backend::Ssm => extract_parameter_name_from_path(secret_id),
+    }
+}
+
+/// Write already-formatted environment file content to disk with the given octal `mode`.
+///
+/// Refuses to write a world-readable `mode` unless `allow_world_readable` is set, since these
+/// files typically hold secrets.
+pub fn create_env_file(
+    content: &str,
+    file_path: &Path,
+    mode: u32,
+    allow_world_readable: bool,
+) -> Result<()> {
+    write_secret_file(content.as_bytes(), file_path, mode, allow_world_readable)
+}
+
+/// Write arbitrary secret bytes to disk with the given octal `mode`, creating the parent
+/// directory if needed.
+///
+/// Refuses to write a world-readable `mode` unless `allow_world_readable` is set, since these
+/// files typically hold secrets.
+pub fn write_secret_file(
+    content: &[u8],
+    file_path: &Path,
+    mode: u32,
+    allow_world_readable: bool,
+) -> Result<()> {
+    if !allow_world_readable && mode & 0o007 != 0 {
+        return Err(anyhow::anyhow!(
+            "Refusing to write {} with world-readable mode {:o}; pass --allow-world-readable to override",
+            file_path.display(),
+            mode
+        ));
+    }
+
     // Ensure the output directory exists
     if let Some(parent) = file_path.parent() {
         println!("Creating output directory: {}", parent.display());
         fs::create_dir_all(parent).context("Failed to create output directory")?;
     }
 
-    // Parse the secret value as JSON and convert to environment variables
-    let env_content = if let Ok(json_value) = serde_json::from_str::<Value>(secret_value) {
-        json_to_env_format(&json_value)?
-    } else {
-        // If it's not JSON, treat it as a single value
-        format!("SECRET_VALUE={}\n", secret_value)
-    };
-
     // Write to file
-    fs::write(file_path, env_content).context("Failed to write environment file")?;
+    fs::write(file_path, content).context("Failed to write secret file")?;
 
-    // Set appropriate permissions (readable by owner and group)
+    // Set the requested permissions
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
         let mut perms = fs::metadata(file_path)?.permissions();
-        perms.set_mode(0o600);
+        perms.set_mode(mode);
         fs::set_permissions(file_path, perms)?;
     }
 
     Ok(())
 }
 
-/// Convert a JSON value to environment variable format
-pub fn json_to_env_format(json_value: &Value) -> Result<String> {
-    let mut env_lines = Vec::new();
+/// Resolve the octal mode to apply to the env file: `VRENV_FILE_MODE` always wins over
+/// `configured_mode`, which falls back to `0o600`.
+pub fn resolve_file_mode(configured_mode: Option<u32>) -> Result<u32> {
+    match std::env::var(FILE_MODE_ENV_VAR) {
+        Ok(raw_mode) => parse_octal_mode(&raw_mode)
+            .with_context(|| format!("Invalid {} value: {}", FILE_MODE_ENV_VAR, raw_mode)),
+        Err(_) => Ok(configured_mode.unwrap_or(DEFAULT_FILE_MODE)),
+    }
+}
+
+/// Parse a mode string like `600` or `0o600` as an octal file mode
+pub fn parse_octal_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .with_context(|| format!("Invalid octal file mode: {}", mode))
+}
+
+/// Turn a raw secret value into an env var map, applying `key_prefix` to each key.
+///
+/// JSON object secrets are expanded key-by-key; anything else (plain text, or JSON that
+/// isn't an object) is kept as a single `SECRET_VALUE` entry.
+fn secret_value_to_env_map(
+    secret_value: &str,
+    key_prefix: Option<&str>,
+    flatten: Option<&FlattenOptions>,
+) -> Result<BTreeMap<String, String>> {
+    if let Ok(json_value @ Value::Object(_)) = serde_json::from_str::<Value>(secret_value) {
+        json_to_env_map(&json_value, key_prefix, flatten)
+    } else {
+        let mut env_map = BTreeMap::new();
+        env_map.insert(prefixed_key("SECRET_VALUE", key_prefix), secret_value.to_string());
+        Ok(env_map)
+    }
+}
+
+/// Merge a base env file, the fetched secret's env map, and matching process environment
+/// variables into a single map, each source overriding the ones before it:
+/// base file -> fetched secret -> process environment (filtered by `key_prefix`).
+pub fn merge_sources(
+    base_file: Option<&str>,
+    secret_env: &BTreeMap<String, String>,
+    key_prefix: Option<&str>,
+) -> Result<BTreeMap<String, String>> {
+    let mut merged = BTreeMap::new();
+
+    if let Some(path) = base_file {
+        merged.extend(parse_env_file(Path::new(path))?);
+    }
+
+    merged.extend(secret_env.clone());
+
+    if let Some(prefix) = key_prefix {
+        for (key, value) in std::env::vars() {
+            if key.starts_with(prefix) {
+                merged.insert(key, value);
+            }
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Parse a `KEY=value` env file, ignoring blank lines and `#` comments
+fn parse_env_file(path: &Path) -> Result<BTreeMap<String, String>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read base env file: {}", path.display()))?;
+
+    let mut env_map = BTreeMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            env_map.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    Ok(env_map)
+}
+
+fn prefixed_key(key: &str, key_prefix: Option<&str>) -> String {
+    match key_prefix {
+        Some(prefix) => format!("{}{}", prefix, key),
+        None => key.to_string(),
+    }
+}
+
+/// Render an env var map as sorted `KEY=value` lines
+pub fn format_env_map(env_map: &BTreeMap<String, String>) -> String {
+    let lines: Vec<String> = env_map
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    lines.join("\n") + "\n"
+}
+
+/// Controls how nested JSON objects/arrays are flattened into env var keys.
+#[derive(Clone, Debug)]
+pub struct FlattenOptions {
+    pub separator: String,
+    pub max_depth: usize,
+}
+
+/// Default nesting depth guard, well beyond any reasonable secret shape.
+pub const DEFAULT_FLATTEN_MAX_DEPTH: usize = 32;
+
+impl Default for FlattenOptions {
+    fn default() -> Self {
+        Self {
+            separator: "_".to_string(),
+            max_depth: DEFAULT_FLATTEN_MAX_DEPTH,
+        }
+    }
+}
+
+/// Convert a JSON object into an env var map, uppercasing keys and applying `key_prefix`.
+///
+/// When `flatten` is `Some`, nested objects and arrays are walked recursively and turned into
+/// dotted/underscored keys (e.g. `{"db":{"host":"x"}}` -> `DB_HOST=x`) instead of being dumped
+/// as a raw JSON blob. `flatten.max_depth` bounds the recursion to guard against pathological
+/// or self-referential-looking structures.
+pub fn json_to_env_map(
+    json_value: &Value,
+    key_prefix: Option<&str>,
+    flatten: Option<&FlattenOptions>,
+) -> Result<BTreeMap<String, String>> {
+    let mut env_map = BTreeMap::new();
 
     match json_value {
         Value::Object(map) => {
             for (key, value) in map {
                 let env_key = key.to_uppercase().replace(['-', ' '], "_");
-                let env_value = match value {
-                    Value::String(s) => s.clone(),
-                    Value::Number(n) => n.to_string(),
-                    Value::Bool(b) => b.to_string(),
-                    Value::Null => String::new(),
-                    _ => serde_json::to_string(value)
-                        .context("Failed to serialize complex JSON value")?,
-                };
-                env_lines.push(format!("{}={}", env_key, env_value));
+                match flatten {
+                    Some(opts) => flatten_into(&mut env_map, &env_key, value, key_prefix, opts, 0)?,
+                    None => {
+                        let env_value = match value {
+                            Value::String(s) => s.clone(),
+                            Value::Number(n) => n.to_string(),
+                            Value::Bool(b) => b.to_string(),
+                            Value::Null => String::new(),
+                            _ => serde_json::to_string(value)
+                                .context("Failed to serialize complex JSON value")?,
+                        };
+                        env_map.insert(prefixed_key(&env_key, key_prefix), env_value);
+                    }
+                }
             }
         }
         _ => {
@@ -127,8 +362,59 @@ pub fn json_to_env_format(json_value: &Value) -> Result<String> {
         }
     }
 
-    env_lines.sort();
-    Ok(env_lines.join("\n") + "\n")
+    Ok(env_map)
+}
+
+/// Recursively flatten `value` under `key` into `env_map`, guarding against runaway depth.
+fn flatten_into(
+    env_map: &mut BTreeMap<String, String>,
+    key: &str,
+    value: &Value,
+    key_prefix: Option<&str>,
+    opts: &FlattenOptions,
+    depth: usize,
+) -> Result<()> {
+    if depth > opts.max_depth {
+        return Err(anyhow::anyhow!(
+            "Refusing to flatten JSON secret: exceeded max depth of {}",
+            opts.max_depth
+        ));
+    }
+
+    match value {
+        Value::Object(map) => {
+            for (child_key, child_value) in map {
+                let child_env_key = child_key.to_uppercase().replace(['-', ' '], "_");
+                let nested_key = format!("{}{}{}", key, opts.separator, child_env_key);
+                flatten_into(env_map, &nested_key, child_value, key_prefix, opts, depth + 1)?;
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                let nested_key = format!("{}{}{}", key, opts.separator, index);
+                flatten_into(env_map, &nested_key, item, key_prefix, opts, depth + 1)?;
+            }
+        }
+        Value::String(s) => {
+            env_map.insert(prefixed_key(key, key_prefix), s.clone());
+        }
+        Value::Number(n) => {
+            env_map.insert(prefixed_key(key, key_prefix), n.to_string());
+        }
+        Value::Bool(b) => {
+            env_map.insert(prefixed_key(key, key_prefix), b.to_string());
+        }
+        Value::Null => {
+            env_map.insert(prefixed_key(key, key_prefix), String::new());
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert a JSON value to environment variable format
+pub fn json_to_env_format(json_value: &Value) -> Result<String> {
+    Ok(format_env_map(&json_to_env_map(json_value, None, None)?))
 }
 
 #[cfg(test)]
@@ -145,6 +431,21 @@ mod tests {
         assert_eq!(extract_secret_name_from_arn(arn_with_path), "secret");
     }
 
+    #[test]
+    fn test_extract_parameter_name_from_path() {
+        assert_eq!(extract_parameter_name_from_path("/my/app/db"), "db");
+        assert_eq!(extract_parameter_name_from_path("db"), "db");
+        assert_eq!(extract_parameter_name_from_path("/my/app/db/"), "db");
+    }
+
+    #[test]
+    fn test_default_env_file_name_differs_by_backend() {
+        let arn = "arn:aws:secretsmanager:us-west-2:123456789012:secret:MySecret-AbCdEf";
+        assert_eq!(default_env_file_name(arn, Backend::SecretsManager), "MySecret");
+
+        assert_eq!(default_env_file_name("/my/app/db", Backend::Ssm), "db");
+    }
+
     #[test]
     fn test_json_to_env_format() {
         let json_str = r#"{"database_url": "postgres://localhost", "api_key": "secret123"}"#;
@@ -165,4 +466,115 @@ mod tests {
         assert!(result.contains("PORT=8080"));
         assert!(result.contains("TIMEOUT="));
     }
+
+    #[test]
+    fn test_merge_sources_secret_overrides_base_file() {
+        let base_path = std::env::temp_dir().join("vrenv_test_merge_sources.env");
+        fs::write(&base_path, "DATABASE_URL=postgres://base\nLOG_LEVEL=info\n").unwrap();
+
+        let mut secret_env = BTreeMap::new();
+        secret_env.insert("DATABASE_URL".to_string(), "postgres://secret".to_string());
+
+        let merged =
+            merge_sources(Some(base_path.to_str().unwrap()), &secret_env, None).unwrap();
+
+        assert_eq!(merged.get("DATABASE_URL").unwrap(), "postgres://secret");
+        assert_eq!(merged.get("LOG_LEVEL").unwrap(), "info");
+
+        fs::remove_file(&base_path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_octal_mode() {
+        assert_eq!(parse_octal_mode("600").unwrap(), 0o600);
+        assert_eq!(parse_octal_mode("0o640").unwrap(), 0o640);
+        assert!(parse_octal_mode("not-octal").is_err());
+    }
+
+    #[test]
+    fn test_create_env_file_rejects_world_readable_mode_by_default() {
+        let path = std::env::temp_dir().join("vrenv_test_world_readable.env");
+        let result = create_env_file("KEY=value\n", &path, 0o644, false);
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_json_to_env_map_flattens_nested_objects_and_arrays() {
+        let json_str = r#"{"db": {"host": "x", "ports": [1, 2]}}"#;
+        let json_value: Value = serde_json::from_str(json_str).unwrap();
+        let env_map = json_to_env_map(&json_value, None, Some(&FlattenOptions::default())).unwrap();
+
+        assert_eq!(env_map.get("DB_HOST").unwrap(), "x");
+        assert_eq!(env_map.get("DB_PORTS_0").unwrap(), "1");
+        assert_eq!(env_map.get("DB_PORTS_1").unwrap(), "2");
+    }
+
+    #[test]
+    fn test_json_to_env_map_flatten_respects_max_depth() {
+        let json_str = r#"{"a": {"b": {"c": "too-deep"}}}"#;
+        let json_value: Value = serde_json::from_str(json_str).unwrap();
+        let opts = FlattenOptions {
+            separator: "_".to_string(),
+            max_depth: 1,
+        };
+
+        assert!(json_to_env_map(&json_value, None, Some(&opts)).is_err());
+    }
+
+    struct FakeBinarySource {
+        bytes: Vec<u8>,
+    }
+
+    #[async_trait::async_trait]
+    impl SecretSource for FakeBinarySource {
+        async fn fetch(&self, _id: &str, _options: &FetchOptions) -> Result<SecretPayload> {
+            Ok(SecretPayload::Binary(self.bytes.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_env_file_from_secret_writes_binary_companion_file_with_mode() {
+        let output_dir = std::env::temp_dir().join("vrenv_test_binary_secret");
+        let _ = fs::remove_dir_all(&output_dir);
+
+        let vrenv = VrEnv::new(Box::new(FakeBinarySource {
+            bytes: vec![0xDE, 0xAD, 0xBE, 0xEF],
+        }));
+        let config = EnvFileConfig {
+            secret_arn: "arn:aws:secretsmanager:us-west-2:123456789012:secret:MySecret-AbCdEf"
+                .to_string(),
+            output_dir: output_dir.to_string_lossy().to_string(),
+            file_name: None,
+            backend: Backend::SecretsManager,
+            base_file: None,
+            key_prefix: None,
+            file_mode: None,
+            allow_world_readable: false,
+            version_id: None,
+            version_stage: None,
+            format: EnvironmentMode::Dotenv,
+            flatten: false,
+            flatten_separator: "_".to_string(),
+            flatten_max_depth: DEFAULT_FLATTEN_MAX_DEPTH,
+        };
+
+        let env_file_path = vrenv.create_env_file_from_secret(config).await.unwrap();
+        let companion_path = output_dir.join("MySecret.bin");
+
+        let env_content = fs::read_to_string(&env_file_path).unwrap();
+        assert!(env_content.contains("SECRET_VALUE_FILE="));
+
+        let companion_bytes = fs::read(&companion_path).unwrap();
+        assert_eq!(companion_bytes, vec![0xDE, 0xAD, 0xBE, 0xEF]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&companion_path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode, 0o600);
+        }
+
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
 }
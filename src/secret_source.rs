@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_sdk_secretsmanager::Client as SecretsManagerClient;
+use aws_sdk_ssm::Client as SsmClient;
+use clap::ValueEnum;
+
+/// A secret value fetched from a backend, before it is turned into an env file.
+pub enum SecretPayload {
+    /// A UTF-8 string secret — the common case
+    Text(String),
+    /// A binary secret (Secrets Manager's `secret_binary`), written to a companion file
+    Binary(Vec<u8>),
+}
+
+/// Optional parameters narrowing which version of a secret to fetch.
+///
+/// Only honored by backends that support versioning (currently Secrets Manager); backends
+/// that don't support it ignore these.
+#[derive(Clone, Debug, Default)]
+pub struct FetchOptions {
+    pub version_id: Option<String>,
+    pub version_stage: Option<String>,
+}
+
+/// Which AWS service to fetch secrets from.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Backend {
+    SecretsManager,
+    Ssm,
+}
+
+/// A source that can fetch a secret value given its identifier.
+///
+/// The identifier format depends on the backend: an ARN (or name) for Secrets Manager,
+/// a parameter name or path for SSM Parameter Store.
+#[async_trait]
+pub trait SecretSource {
+    async fn fetch(&self, id: &str, options: &FetchOptions) -> Result<SecretPayload>;
+}
+
+/// Fetches secrets from AWS Secrets Manager via `get_secret_value`.
+pub struct SecretsManagerSource {
+    client: SecretsManagerClient,
+}
+
+impl SecretsManagerSource {
+    pub fn new(client: SecretsManagerClient) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl SecretSource for SecretsManagerSource {
+    async fn fetch(&self, id: &str, options: &FetchOptions) -> Result<SecretPayload> {
+        let mut request = self.client.get_secret_value().secret_id(id);
+        if let Some(version_id) = &options.version_id {
+            request = request.version_id(version_id);
+        }
+        if let Some(version_stage) = &options.version_stage {
+            request = request.version_stage(version_stage);
+        }
+
+        let response = request
+            .send()
+            .await
+            .context("Failed to fetch secret from AWS Secrets Manager")?;
+
+        if let Some(value) = response.secret_string() {
+            return Ok(SecretPayload::Text(value.to_string()));
+        }
+
+        if let Some(blob) = response.secret_binary() {
+            return Ok(SecretPayload::Binary(blob.as_ref().to_vec()));
+        }
+
+        Err(anyhow::anyhow!(
+            "Secret does not contain a string or binary value"
+        ))
+    }
+}
+
+/// Fetches parameters from AWS Systems Manager Parameter Store via `get_parameter`.
+///
+/// Parameters are always fetched with `--with-decryption`, which is required to read back
+/// `SecureString` values (and in turn requires `kms:Decrypt` on the caller) and is a no-op
+/// for plain `String`/`StringList` parameters. SSM has no notion of version id/stage, so
+/// `FetchOptions` is ignored here.
+pub struct SsmSource {
+    client: SsmClient,
+}
+
+impl SsmSource {
+    pub fn new(client: SsmClient) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl SecretSource for SsmSource {
+    async fn fetch(&self, id: &str, _options: &FetchOptions) -> Result<SecretPayload> {
+        let response = self
+            .client
+            .get_parameter()
+            .name(id)
+            .with_decryption(true)
+            .send()
+            .await
+            .context("Failed to fetch parameter from AWS SSM Parameter Store")?;
+
+        let value = response
+            .parameter()
+            .and_then(|p| p.value())
+            .context("Parameter does not contain a value")?
+            .to_string();
+
+        Ok(SecretPayload::Text(value))
+    }
+}